@@ -1,19 +1,14 @@
 use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, Luma};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-const PUZZLE_GRID_SIZE: usize = 16;
 const HASH_MAGIC_NUMBER: u64 = 0x9e379967;
-const IMAGE_WIDTH: u32 = 3840;
-const IMAGE_HEIGHT: u32 = 2160;
-const FIRST_COL_WIDTH: u32 = 240;
-const FIRST_ROW_HEIGHT: u32 = 135;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Side {
     Left,
     Top,
@@ -21,53 +16,169 @@ enum Side {
     Bottom,
 }
 
-const OFFSETS: [(i32, i32); 4] = [(-1, 0), (0, -1), (1, 0), (0, 1)];
+/// One of the 8 dihedral orientations a piece can be placed in: a quarter-turn
+/// count (clockwise) plus an optional horizontal flip applied after rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pose {
+    rotation: u8,
+    flipped: bool,
+}
+
+const IDENTITY_POSE: Pose = Pose {
+    rotation: 0,
+    flipped: false,
+};
+
+const POSES: [Pose; 8] = [
+    Pose {
+        rotation: 0,
+        flipped: false,
+    },
+    Pose {
+        rotation: 1,
+        flipped: false,
+    },
+    Pose {
+        rotation: 2,
+        flipped: false,
+    },
+    Pose {
+        rotation: 3,
+        flipped: false,
+    },
+    Pose {
+        rotation: 0,
+        flipped: true,
+    },
+    Pose {
+        rotation: 1,
+        flipped: true,
+    },
+    Pose {
+        rotation: 2,
+        flipped: true,
+    },
+    Pose {
+        rotation: 3,
+        flipped: true,
+    },
+];
+
+fn pose_index(pose: Pose) -> usize {
+    pose.rotation as usize + if pose.flipped { 4 } else { 0 }
+}
+
+/// Returns `image` rotated clockwise by `pose.rotation` quarter turns and then
+/// mirrored horizontally if `pose.flipped` is set.
+fn apply_pose(image: &DynamicImage, pose: Pose) -> DynamicImage {
+    let mut oriented = match pose.rotation {
+        1 => image.rotate90(),
+        2 => image.rotate180(),
+        3 => image.rotate270(),
+        _ => image.clone(),
+    };
+    if pose.flipped {
+        oriented = oriented.fliph();
+    }
+    oriented
+}
 
 #[derive(Debug, Clone)]
 struct PuzzlePiece {
     image: DynamicImage,
     col: i32,
     row: i32,
-    edge_hashes: [u64; 4],
+    rotation: u8,
+    flipped: bool,
+    edge_hashes: [EdgeKey; 4],
+    pose_edge_hashes: [[EdgeKey; 4]; 8],
 }
 
-fn compute_hash(edge: &image::SubImage<&image::ImageBuffer<Luma<u8>, Vec<u8>>>) -> u64 {
+/// An edge fingerprint, canonical across reading direction (see `canonical_edge`).
+type EdgeKey = u64;
+
+fn hash_pixels(pixels: impl Iterator<Item = u8>) -> u64 {
     let mut hash = 0u64;
-    for pixel in edge.pixels() {
-        hash = hash.wrapping_add((pixel.2[0] as u64 / 10).wrapping_add(HASH_MAGIC_NUMBER));
+    for value in pixels {
+        hash = hash.wrapping_add((value as u64 / 10).wrapping_add(HASH_MAGIC_NUMBER));
         hash = hash.wrapping_add(hash.wrapping_shl(6));
         hash = hash.wrapping_add(hash.wrapping_shr(2));
     }
     hash
 }
 
+/// Folds an edge's pixels into a fingerprint that is the same regardless of
+/// which end it's read from, by taking the smaller of the forward and
+/// reversed hashes.
+fn canonical_edge(pixels: &[u8]) -> EdgeKey {
+    let forward = hash_pixels(pixels.iter().copied());
+    let reverse = hash_pixels(pixels.iter().rev().copied());
+    forward.min(reverse)
+}
+
+fn edge_hashes_of(gray: &image::ImageBuffer<Luma<u8>, Vec<u8>>) -> [EdgeKey; 4] {
+    let left: Vec<u8> = gray.view(0, 0, 1, gray.height()).pixels().map(|p| p.2[0]).collect();
+    let top: Vec<u8> = gray.view(0, 0, gray.width(), 1).pixels().map(|p| p.2[0]).collect();
+    let right: Vec<u8> = gray
+        .view(gray.width() - 1, 0, 1, gray.height())
+        .pixels()
+        .map(|p| p.2[0])
+        .collect();
+    let bottom: Vec<u8> = gray
+        .view(0, gray.height() - 1, gray.width(), 1)
+        .pixels()
+        .map(|p| p.2[0])
+        .collect();
+
+    [
+        canonical_edge(&left),
+        canonical_edge(&top),
+        canonical_edge(&right),
+        canonical_edge(&bottom),
+    ]
+}
+
 impl PuzzlePiece {
     fn new(image: DynamicImage) -> Self {
-        let (width, height) = image.dimensions();
+        let pose_edge_hashes = Self::compute_pose_edge_hashes(&image);
+        let edge_hashes = pose_edge_hashes[pose_index(IDENTITY_POSE)];
 
-        let mut piece = PuzzlePiece {
+        PuzzlePiece {
             image,
-            col: if width == FIRST_COL_WIDTH { 0 } else { -1 },
-            row: if height == FIRST_ROW_HEIGHT { 0 } else { -1 },
-            edge_hashes: [0; 4],
-        };
-        piece.compute_edge_hashes();
-        piece
+            col: -1,
+            row: -1,
+            rotation: IDENTITY_POSE.rotation,
+            flipped: IDENTITY_POSE.flipped,
+            edge_hashes,
+            pose_edge_hashes,
+        }
     }
 
-    fn rect(&self) -> (u32, u32, u32, u32) {
-        let x = FIRST_COL_WIDTH * self.col as u32 - if self.col > 0 { 1 } else { 0 };
-        let y = FIRST_ROW_HEIGHT * self.row as u32 - if self.row > 0 { 1 } else { 0 };
-        (x, y, self.image.width(), self.image.height())
+    /// Computes the canonical edge fingerprints for every dihedral pose of
+    /// `image`, so assembly can recognize a neighbor regardless of how it was
+    /// scanned in or which direction its edge is read from.
+    fn compute_pose_edge_hashes(image: &DynamicImage) -> [[EdgeKey; 4]; 8] {
+        let mut hashes = [[0 as EdgeKey; 4]; 8];
+        for (i, pose) in POSES.iter().enumerate() {
+            let oriented = apply_pose(image, *pose);
+            hashes[i] = edge_hashes_of(&oriented.to_luma8());
+        }
+        hashes
     }
 
-    fn compute_edge_hashes(&mut self) {
-        let gray = self.image.to_luma8();
-        self.edge_hashes[0] = compute_hash(&gray.view(0, 0, 1, gray.height()));
-        self.edge_hashes[1] = compute_hash(&gray.view(0, 0, gray.width(), 1));
-        self.edge_hashes[2] = compute_hash(&gray.view(gray.width() - 1, 0, 1, gray.height()));
-        self.edge_hashes[3] = compute_hash(&gray.view(0, gray.height() - 1, gray.width(), 1));
-    }
+}
+
+/// The (width, height) of the piece's image as it will actually be blitted:
+/// rotated/flipped per its placed pose.
+fn oriented_dimensions(piece: &PuzzlePiece) -> (u32, u32) {
+    apply_pose(
+        &piece.image,
+        Pose {
+            rotation: piece.rotation,
+            flipped: piece.flipped,
+        },
+    )
+    .dimensions()
 }
 
 fn load_puzzle<P: AsRef<Path>>(path: P) -> Result<Vec<PuzzlePiece>, Box<dyn Error + Send + Sync>> {
@@ -81,65 +192,387 @@ fn load_puzzle<P: AsRef<Path>>(path: P) -> Result<Vec<PuzzlePiece>, Box<dyn Erro
         .collect()
 }
 
-type HashMapType = [HashMap<u64, Vec<usize>>; 4];
+/// An (edge side, canonical key) -> (piece, pose) index used by the solver to
+/// find every piece/pose that could legally sit at a given side of a cell.
+type EdgeCache = HashMap<(Side, EdgeKey), Vec<(usize, Pose)>>;
 
-fn build_hash_map(pieces: &[PuzzlePiece]) -> HashMapType {
-    let mut hash_maps: HashMapType = Default::default();
+fn build_edge_cache(pieces: &[PuzzlePiece]) -> EdgeCache {
+    let mut cache: EdgeCache = HashMap::new();
     for (i, piece) in pieces.iter().enumerate() {
-        for j in 0..4 {
-            hash_maps[j]
-                .entry(piece.edge_hashes[j])
-                .or_default()
-                .push(i);
+        for (pose_idx, pose) in POSES.iter().enumerate() {
+            for side in [Side::Left, Side::Top, Side::Right, Side::Bottom] {
+                cache
+                    .entry((side, piece.pose_edge_hashes[pose_idx][side as usize]))
+                    .or_default()
+                    .push((i, *pose));
+            }
         }
     }
-    hash_maps
-}
-
-fn assemble_puzzle(pieces: &mut [PuzzlePiece]) {
-    pieces.sort_unstable_by(|a, b| {
-        if (a.col == 0 || a.row == 0) && (b.col != 0 && b.row != 0) {
-            std::cmp::Ordering::Less
-        } else if (b.col == 0 || b.row == 0) && (a.col != 0 && a.row != 0) {
-            std::cmp::Ordering::Greater
-        } else if a.col == 0 && a.row == 0 {
-            std::cmp::Ordering::Less
-        } else if b.col == 0 && b.row == 0 {
-            std::cmp::Ordering::Greater
-        } else {
-            (a.col + a.row).cmp(&(b.col + b.row))
+    cache
+}
+
+/// A piece/pose already committed to a cell.
+#[derive(Clone, Copy)]
+struct Placement {
+    piece: usize,
+    pose: Pose,
+}
+
+/// The edge keys a candidate for this cell must satisfy, one per side that
+/// already has a placed neighbor. `None` means that side is still open.
+#[derive(Default)]
+struct Constraint {
+    north: Option<EdgeKey>,
+    east: Option<EdgeKey>,
+    south: Option<EdgeKey>,
+    west: Option<EdgeKey>,
+}
+
+fn constraint_for(
+    cell: (i32, i32),
+    placed_tiles: &HashMap<(i32, i32), Placement>,
+    pieces: &[PuzzlePiece],
+) -> Constraint {
+    let (col, row) = cell;
+    let shared_edge = |neighbor: (i32, i32), neighbor_side: Side| {
+        placed_tiles.get(&neighbor).map(|placement| {
+            pieces[placement.piece].pose_edge_hashes[pose_index(placement.pose)][neighbor_side as usize]
+        })
+    };
+    Constraint {
+        north: shared_edge((col, row - 1), Side::Bottom),
+        east: shared_edge((col + 1, row), Side::Left),
+        south: shared_edge((col, row + 1), Side::Top),
+        west: shared_edge((col - 1, row), Side::Right),
+    }
+}
+
+/// Every free piece/pose whose edges satisfy every constraint side that's
+/// already pinned down by a placed neighbor.
+fn candidates_for(
+    constraint: &Constraint,
+    edge_cache: &EdgeCache,
+    pieces: &[PuzzlePiece],
+    free_tiles: &HashSet<usize>,
+) -> Vec<(usize, Pose)> {
+    let present: Vec<(Side, EdgeKey)> = [
+        (Side::Top, constraint.north),
+        (Side::Right, constraint.east),
+        (Side::Bottom, constraint.south),
+        (Side::Left, constraint.west),
+    ]
+    .into_iter()
+    .filter_map(|(side, key)| key.map(|key| (side, key)))
+    .collect();
+
+    let Some((&(anchor_side, anchor_key), rest)) = present.split_first() else {
+        return free_tiles
+            .iter()
+            .flat_map(|&i| POSES.iter().map(move |&pose| (i, pose)))
+            .collect();
+    };
+
+    edge_cache
+        .get(&(anchor_side, anchor_key))
+        .into_iter()
+        .flatten()
+        .filter(|(i, pose)| {
+            free_tiles.contains(i)
+                && rest.iter().all(|&(side, key)| {
+                    pieces[*i].pose_edge_hashes[pose_index(*pose)][side as usize] == key
+                })
+        })
+        .copied()
+        .collect()
+}
+
+/// Backtracking constraint solver: fills `cells` in order, picking for each
+/// one a free piece/pose consistent with its already-placed neighbors, and
+/// undoing the choice and trying the next candidate on a dead end.
+fn solve(
+    cells: &[(i32, i32)],
+    pieces: &[PuzzlePiece],
+    edge_cache: &EdgeCache,
+    placed_tiles: &mut HashMap<(i32, i32), Placement>,
+    free_tiles: &mut HashSet<usize>,
+) -> bool {
+    let Some((&cell, remaining)) = cells.split_first() else {
+        return true;
+    };
+
+    // A cell may already be filled by a pre-seeded placement (e.g. the
+    // detected corner); leave it alone and move on instead of overwriting it
+    // with a different piece and losing the seed's piece from both maps.
+    if placed_tiles.contains_key(&cell) {
+        return solve(remaining, pieces, edge_cache, placed_tiles, free_tiles);
+    }
+
+    let constraint = constraint_for(cell, placed_tiles, pieces);
+    let candidates = candidates_for(&constraint, edge_cache, pieces, free_tiles);
+
+    for (piece, pose) in candidates {
+        placed_tiles.insert(cell, Placement { piece, pose });
+        free_tiles.remove(&piece);
+
+        if solve(remaining, pieces, edge_cache, placed_tiles, free_tiles) {
+            return true;
         }
-    });
 
-    let hash_maps = build_hash_map(pieces);
-    let mut stack = vec![0];
+        placed_tiles.remove(&cell);
+        free_tiles.insert(piece);
+    }
 
-    while let Some(current_index) = stack.pop() {
-        let (col, row): (i32, i32) = {
-            let current_piece = &pieces[current_index];
-            (current_piece.col, current_piece.row)
-        };
-        if col == PUZZLE_GRID_SIZE as i32 - 1 && row == PUZZLE_GRID_SIZE as i32 - 1 {
-            continue;
+    false
+}
+
+/// Counts, for every canonical edge key, how many piece edges carry it. A key
+/// held by only one edge borders nothing else: it's an outer edge of the
+/// whole puzzle, not a seam between two pieces.
+fn edge_share_counts(pieces: &[PuzzlePiece]) -> HashMap<EdgeKey, usize> {
+    let mut counts: HashMap<EdgeKey, usize> = HashMap::new();
+    for piece in pieces {
+        for &key in &piece.edge_hashes {
+            *counts.entry(key).or_insert(0) += 1;
         }
+    }
+    counts
+}
 
-        for (side, opposite_side) in [(Side::Right, Side::Left), (Side::Bottom, Side::Top)] {
-            if let Some(match_index) = hash_maps[opposite_side as usize]
-                .get(&pieces[current_index].edge_hashes[side as usize])
-                .and_then(|matches| {
-                    matches.iter().find(|&&id| {
-                        id != current_index && (pieces[id].col == -1 || pieces[id].row == -1)
-                    })
-                })
-            {
-                let (col_offset, row_offset) = OFFSETS[side as usize];
-                pieces[*match_index].col = col + col_offset;
-                pieces[*match_index].row = row + row_offset;
+/// The piece's identity-pose sides whose edge key matches no other piece,
+/// i.e. the sides of this piece that sit on the outer frame of the puzzle.
+fn frame_sides(piece: &PuzzlePiece, edge_share_counts: &HashMap<EdgeKey, usize>) -> Vec<Side> {
+    [Side::Left, Side::Top, Side::Right, Side::Bottom]
+        .into_iter()
+        .filter(|&side| edge_share_counts[&piece.edge_hashes[side as usize]] == 1)
+        .collect()
+}
+
+fn are_adjacent(a: Side, b: Side) -> bool {
+    !matches!(
+        (a, b),
+        (Side::Left, Side::Right) | (Side::Right, Side::Left) | (Side::Top, Side::Bottom) | (Side::Bottom, Side::Top)
+    )
+}
+
+/// Whether `piece` sits on an actual corner of the puzzle: exactly two
+/// unmatched sides that are adjacent. Two unmatched *opposite* sides (e.g. an
+/// interior piece of a 1-wide strip, unmatched on both Top and Bottom) isn't a
+/// corner and must not be counted as one.
+fn is_corner(piece: &PuzzlePiece, edge_share_counts: &HashMap<EdgeKey, usize>) -> bool {
+    let sides = frame_sides(piece, edge_share_counts);
+    sides.len() == 2 && are_adjacent(sides[0], sides[1])
+}
+
+/// Finds the pose of `piece` that rotates/flips its two frame sides onto the
+/// grid's top-left corner, i.e. onto `Side::Top` and `Side::Left`.
+fn corner_seed_pose(piece: &PuzzlePiece, edge_share_counts: &HashMap<EdgeKey, usize>) -> Option<Pose> {
+    POSES.iter().copied().find(|&pose| {
+        let hashes = piece.pose_edge_hashes[pose_index(pose)];
+        edge_share_counts[&hashes[Side::Top as usize]] == 1
+            && edge_share_counts[&hashes[Side::Left as usize]] == 1
+    })
+}
+
+/// Walks the (0, 0)..(cols, rows) grid around its perimeter first, clockwise
+/// from the top-left corner, then fills the interior in row-major order.
+/// Along the walk most border cells are constrained only by the neighbor placed
+/// just before them (the grid boundary itself adds no constraint); interior cells
+/// next to the frame are then constrained by every placed frame neighbor.
+fn frame_first_cells(cols: i32, rows: i32) -> Vec<(i32, i32)> {
+    let mut seen = HashSet::new();
+    let mut cells = Vec::new();
+    let mut visit = |cell: (i32, i32), cells: &mut Vec<(i32, i32)>| {
+        if seen.insert(cell) {
+            cells.push(cell);
+        }
+    };
 
-                stack.push(*match_index);
+    for col in 0..cols {
+        visit((col, 0), &mut cells);
+    }
+    for row in 0..rows {
+        visit((cols - 1, row), &mut cells);
+    }
+    for col in (0..cols).rev() {
+        visit((col, rows - 1), &mut cells);
+    }
+    for row in (0..rows).rev() {
+        visit((0, row), &mut cells);
+    }
+    for row in 0..rows {
+        for col in 0..cols {
+            visit((col, row), &mut cells);
+        }
+    }
+
+    cells
+}
+
+/// The pure arithmetic behind [`grid_dimensions`]: given the total piece
+/// count and how many of them are corners/plain border pieces, solves for
+/// (cols, rows) via `cols + rows` and `cols * rows`, falling back to a square
+/// guess if the frame counts don't pin down a unique rectangle.
+///
+/// A 1-wide/1-tall puzzle has no real corners at all (see [`is_corner`]), so
+/// this can only ever resolve it through the square fallback below: a 1xN
+/// strip gives `None`, unless N is a perfect square, in which case it gives
+/// the square (√N, √N) rather than the strip's shape. That's a hard limitation, not
+/// a transient guess: such puzzles need a different detection strategy to
+/// assemble correctly, which this solver doesn't implement.
+fn grid_dimensions_from_frame(total: usize, corners: usize, borders: usize) -> Option<(usize, usize)> {
+    if corners == 4 {
+        let sum = (corners + borders) as f64 / 2.0 + 2.0;
+        let discriminant = sum * sum - 4.0 * total as f64;
+        if discriminant >= 0.0 {
+            let sqrt_d = discriminant.sqrt();
+            let cols = ((sum + sqrt_d) / 2.0).round() as usize;
+            let rows = ((sum - sqrt_d) / 2.0).round() as usize;
+            if cols > 0 && rows > 0 && cols * rows == total {
+                return Some((cols, rows));
             }
         }
     }
+
+    let side = (total as f64).sqrt().round() as usize;
+    (side * side == total).then_some((side, side))
+}
+
+/// Infers the puzzle's (cols, rows) from how many pieces sit on its frame:
+/// corners have two adjacent unmatched sides, plain border pieces have one,
+/// and a rectangle's perimeter is `2 * (cols + rows) - 4`. That plus the
+/// total piece count pins down both dimensions (see
+/// [`grid_dimensions_from_frame`]), and this errors out rather than guessing
+/// a shape that can't hold every piece.
+fn grid_dimensions(
+    pieces: &[PuzzlePiece],
+    counts: &HashMap<EdgeKey, usize>,
+) -> Result<(usize, usize), Box<dyn Error + Send + Sync>> {
+    let total = pieces.len();
+    let corners = pieces.iter().filter(|p| is_corner(p, counts)).count();
+    let borders = pieces.iter().filter(|p| frame_sides(p, counts).len() == 1).count();
+
+    grid_dimensions_from_frame(total, corners, borders).ok_or_else(|| {
+        format!(
+            "could not infer a grid shape holding all {total} pieces (detected {corners} corners, \
+             {borders} border pieces) — note that 1-wide/1-tall puzzles aren't supported"
+        )
+        .into()
+    })
+}
+
+fn assemble_puzzle(
+    pieces: &mut [PuzzlePiece],
+) -> Result<(usize, usize), Box<dyn Error + Send + Sync>> {
+    let counts = edge_share_counts(pieces);
+    let (cols, rows) = grid_dimensions(pieces, &counts)?;
+    let cells = frame_first_cells(cols as i32, rows as i32);
+
+    let edge_cache = build_edge_cache(pieces);
+    let mut placed_tiles: HashMap<(i32, i32), Placement> = HashMap::new();
+    let mut free_tiles: HashSet<usize> = (0..pieces.len()).collect();
+
+    // Seed the solve from a detected corner piece, oriented so its two frame
+    // sides land on the grid's top-left edges, so the search explores from a
+    // fixed, known-good anchor instead of every free piece in every pose.
+    let seed = pieces.iter().enumerate().find_map(|(i, piece)| {
+        is_corner(piece, &counts)
+            .then(|| corner_seed_pose(piece, &counts).map(|pose| (i, pose)))
+            .flatten()
+    });
+    if let Some((seed, pose)) = seed {
+        placed_tiles.insert((0, 0), Placement { piece: seed, pose });
+        free_tiles.remove(&seed);
+    }
+
+    if !solve(&cells, pieces, &edge_cache, &mut placed_tiles, &mut free_tiles) {
+        return Err(format!(
+            "could not place all {} pieces into a {cols}x{rows} grid \
+             (the edge hashes don't describe a consistent layout)",
+            pieces.len()
+        )
+        .into());
+    }
+
+    for (&(col, row), placement) in &placed_tiles {
+        let piece = &mut pieces[placement.piece];
+        piece.col = col;
+        piece.row = row;
+        piece.rotation = placement.pose.rotation;
+        piece.flipped = placement.pose.flipped;
+        piece.edge_hashes = piece.pose_edge_hashes[pose_index(placement.pose)];
+    }
+
+    Ok((cols, rows))
+}
+
+/// The pixel offset of each column/row in the assembled grid, and the
+/// resulting canvas size — derived from the actual sizes of the pieces
+/// placed along the top row and left column, not a fixed per-piece size.
+struct Grid {
+    col_x: Vec<u32>,
+    row_y: Vec<u32>,
+    width: u32,
+    height: u32,
+}
+
+fn prefix_sums(values: &[u32]) -> Vec<u32> {
+    let mut sums = Vec::with_capacity(values.len());
+    let mut offset = 0;
+    for &value in values {
+        sums.push(offset);
+        offset += value;
+    }
+    sums
+}
+
+fn build_grid(
+    pieces: &[PuzzlePiece],
+    cols: usize,
+    rows: usize,
+) -> Result<Grid, Box<dyn Error + Send + Sync>> {
+    let by_position: HashMap<(i32, i32), &PuzzlePiece> = pieces
+        .iter()
+        .filter(|p| p.col >= 0 && p.row >= 0)
+        .map(|p| ((p.col, p.row), p))
+        .collect();
+
+    let col_widths: Vec<u32> = (0..cols as i32)
+        .map(|col| {
+            by_position
+                .get(&(col, 0))
+                .map_or(0, |piece| oriented_dimensions(piece).0)
+        })
+        .collect();
+    let row_heights: Vec<u32> = (0..rows as i32)
+        .map(|row| {
+            by_position
+                .get(&(0, row))
+                .map_or(0, |piece| oriented_dimensions(piece).1)
+        })
+        .collect();
+
+    // A piece's oriented size must agree with the column/row it's going into
+    // (a rotated piece can otherwise come out wider/taller than its slot,
+    // which would misalign the grid or panic the blit further down).
+    for (&(col, row), piece) in &by_position {
+        let (width, height) = oriented_dimensions(piece);
+        let expected_width = col_widths[col as usize];
+        let expected_height = row_heights[row as usize];
+        if width != expected_width || height != expected_height {
+            return Err(format!(
+                "piece at ({col}, {row}) is {width}x{height} in its placed pose, \
+                 but column {col} expects width {expected_width} and row {row} expects height {expected_height}"
+            )
+            .into());
+        }
+    }
+
+    Ok(Grid {
+        col_x: prefix_sums(&col_widths),
+        row_y: prefix_sums(&row_heights),
+        width: col_widths.iter().sum(),
+        height: row_heights.iter().sum(),
+    })
 }
 
 fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -153,21 +586,31 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     println!("Load puzzle time: {}ms", timer.elapsed().as_millis());
     timer = Instant::now();
 
-    assemble_puzzle(&mut puzzle_pieces);
+    let (cols, rows) = assemble_puzzle(&mut puzzle_pieces)?;
 
     println!("Assemble puzzle time: {}ms", timer.elapsed().as_millis());
     timer = Instant::now();
 
-    let result = Arc::new(Mutex::new(ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT)));
+    let grid = build_grid(&puzzle_pieces, cols, rows)?;
+    let result = Arc::new(Mutex::new(ImageBuffer::new(grid.width, grid.height)));
     puzzle_pieces.par_iter().for_each(|piece| {
         if piece.col >= 0
             && piece.row >= 0
-            && piece.col < PUZZLE_GRID_SIZE as i32
-            && piece.row < PUZZLE_GRID_SIZE as i32
+            && (piece.col as usize) < cols
+            && (piece.row as usize) < rows
         {
-            let (x, y, width, height) = piece.rect();
+            let oriented = apply_pose(
+                &piece.image,
+                Pose {
+                    rotation: piece.rotation,
+                    flipped: piece.flipped,
+                },
+            );
+            let (width, height) = oriented.dimensions();
+            let x = grid.col_x[piece.col as usize];
+            let y = grid.row_y[piece.row as usize];
             let mut buffer = ImageBuffer::new(width, height);
-            for (dx, dy, pixel) in piece.image.to_rgb8().enumerate_pixels() {
+            for (dx, dy, pixel) in oriented.to_rgb8().enumerate_pixels() {
                 buffer.put_pixel(dx, dy, *pixel);
             }
 
@@ -187,3 +630,65 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_edge_is_invariant_to_reading_direction() {
+        let forward = vec![10, 20, 30, 40];
+        let reversed: Vec<u8> = forward.iter().rev().copied().collect();
+        assert_eq!(canonical_edge(&forward), canonical_edge(&reversed));
+    }
+
+    #[test]
+    fn canonical_edge_differs_for_different_pixels() {
+        assert_ne!(canonical_edge(&[10, 20, 30, 40]), canonical_edge(&[200, 210, 220, 230]));
+    }
+
+    #[test]
+    fn frame_first_cells_visits_every_cell_exactly_once() {
+        let cells = frame_first_cells(4, 3);
+        assert_eq!(cells.len(), 12);
+
+        let unique: HashSet<_> = cells.iter().copied().collect();
+        assert_eq!(unique.len(), 12);
+        for col in 0..4 {
+            for row in 0..3 {
+                assert!(unique.contains(&(col, row)));
+            }
+        }
+    }
+
+    #[test]
+    fn frame_first_cells_walks_the_perimeter_before_the_interior() {
+        // A 3x3 grid's perimeter is its 8 outer cells; (1, 1) is the only
+        // interior cell, so it must be visited last.
+        let cells = frame_first_cells(3, 3);
+        assert_eq!(cells[0], (0, 0));
+        assert_eq!(cells[8], (1, 1));
+    }
+
+    #[test]
+    fn grid_dimensions_solves_a_square_from_its_frame() {
+        // A 4x4 grid has 4 corners and 8 plain border pieces.
+        assert_eq!(grid_dimensions_from_frame(16, 4, 8), Some((4, 4)));
+    }
+
+    #[test]
+    fn grid_dimensions_solves_a_rectangle_from_its_frame() {
+        // A 5x3 grid's perimeter is 2 * (5 + 3) - 4 = 12: 4 corners, 8 borders.
+        assert_eq!(grid_dimensions_from_frame(15, 4, 8), Some((5, 3)));
+    }
+
+    #[test]
+    fn grid_dimensions_falls_back_to_a_square_when_the_frame_is_ambiguous() {
+        assert_eq!(grid_dimensions_from_frame(9, 0, 0), Some((3, 3)));
+    }
+
+    #[test]
+    fn grid_dimensions_gives_up_on_a_shape_that_cant_hold_every_piece() {
+        assert_eq!(grid_dimensions_from_frame(7, 0, 0), None);
+    }
+}